@@ -2,9 +2,20 @@
 
 use std::collections::HashMap;
 
+// Shares the PBFT-safety primitives (quorum collection, rolling finality,
+// fork choice, slashing protection) with the blockchain-flavored engine in
+// src/consensus.rs, rather than carrying a second copy of them -- a bug in
+// one used to exist identically in the other (see the chunk0-2 vote-
+// attribution fix).
+#[path = "src/consensus_common.rs"]
+mod consensus_common;
+use consensus_common::{
+    run_prepare_commit_phases, AggregateSignature, Bitfield, CommitteeMember, ForkChoice, NewView,
+    PbftBlock, PreparedProof, PublicKey, RollingFinality, Signature, SlashingProtection, Step,
+    ViewChange, VoteCollector,
+};
+
 // Mock types for demonstration
-type Signature = Vec<u8>;
-type PublicKey = Vec<u8>;
 type Hash = Vec<u8>;
 
 #[derive(Debug, Clone)]
@@ -32,8 +43,13 @@ pub struct BlockHeader {
     validator_pub_key: PublicKey, // Public key of the block proposer
     // PBFT-related fields
     pre_prepare_signatures: Vec<Signature>,
-    prepare_signatures: Vec<Signature>,
-    commit_signatures: Vec<Signature>,
+    // Prepare/Commit phases are each collapsed to one aggregate signature
+    // plus a bitfield marking which committee members contributed, instead
+    // of one signature per validator.
+    prepare_aggregate: AggregateSignature,
+    prepare_bitfield: Bitfield,
+    commit_aggregate: AggregateSignature,
+    commit_bitfield: Bitfield,
 }
 
 #[derive(Debug, Clone)]
@@ -50,20 +66,139 @@ pub struct Validator {
     geopolitical_zone: String, // e.g., "South-West", "North-Central"
 }
 
+impl CommitteeMember for Validator {
+    fn pub_key(&self) -> &PublicKey {
+        &self.pub_key
+    }
+
+    fn stake(&self) -> u64 {
+        self.stake
+    }
+}
+
+impl PbftBlock for Block {
+    fn height(&self) -> u64 {
+        self.header.height
+    }
+
+    fn merkle_root(&self) -> &[u8] {
+        &self.header.merkle_root
+    }
+
+    fn set_prepare_phase(&mut self, aggregate: AggregateSignature, bitfield: Bitfield) {
+        self.header.prepare_aggregate = aggregate;
+        self.header.prepare_bitfield = bitfield;
+    }
+
+    fn set_commit_phase(&mut self, aggregate: AggregateSignature, bitfield: Bitfield) {
+        self.header.commit_aggregate = aggregate;
+        self.header.commit_bitfield = bitfield;
+    }
+
+    fn commit_bitfield(&self) -> &Bitfield {
+        &self.header.commit_bitfield
+    }
+}
+
 pub struct NaijaConsensusEngine {
     current_committee: Vec<Validator>,
     faulty_nodes_limit: usize, // 'f' in 2f+1
+    // Height from which the rolling finality quorum switches from a simple
+    // majority (> n/2) to a 2/3 supermajority (> 2n/3) of distinct signers.
+    two_thirds_majority_transition: u64,
+    rolling_finality: RollingFinality,
+    vote_collector: VoteCollector,
+    // Current PBFT view; bumped by a successful view-change.
+    view: u64,
+    // Merkle root of the block that reached a Prepare quorum at (height, view).
+    prepared_blocks: HashMap<(u64, u64), Hash>,
+    // Buffered ViewChange messages, keyed by the view being requested.
+    view_changes: HashMap<u64, HashMap<PublicKey, ViewChange>>,
+    fork_choice: ForkChoice,
+    // Guards this node's own keys against self-equivocation after a restart.
+    slashing_protection: SlashingProtection,
     // ... other blockchain state (e.g., chain, mempool, voter registry hash)
 }
 
 impl NaijaConsensusEngine {
-    pub fn new(initial_validators: Vec<Validator>, faulty_limit: usize) -> Self {
+    pub fn new(initial_validators: Vec<Validator>, faulty_limit: usize, two_thirds_majority_transition: u64) -> Self {
+        let mut slashing_protection = SlashingProtection::new();
+        for validator in &initial_validators {
+            slashing_protection.register_validator(validator.pub_key.clone());
+        }
+
         NaijaConsensusEngine {
             current_committee: initial_validators,
             faulty_nodes_limit: faulty_limit,
+            two_thirds_majority_transition,
+            rolling_finality: RollingFinality::new(),
+            vote_collector: VoteCollector::new(),
+            view: 0,
+            prepared_blocks: HashMap::new(),
+            view_changes: HashMap::new(),
+            fork_choice: ForkChoice::new(),
+            slashing_protection,
         }
     }
 
+    /// Deterministically selects the leader for `view` from the committee.
+    pub fn select_leader(view: u64, committee: &[Validator]) -> PublicKey {
+        committee[(view as usize) % committee.len()].pub_key.clone()
+    }
+
+    /// Called when this validator's round timer expires without the current
+    /// view reaching Commit. Penalizes the leader that was supposed to drive
+    /// this view, then returns the ViewChange to broadcast, carrying the
+    /// highest Prepare-quorum block seen at `height` so it isn't lost.
+    pub fn on_timeout(&mut self, height: u64, self_key: PublicKey) -> ViewChange {
+        let abandoned_leader = Self::select_leader(self.view, &self.current_committee);
+        if let Some(validator) = self.current_committee.iter_mut().find(|v| v.pub_key == abandoned_leader) {
+            validator.reputation_score = validator.reputation_score.saturating_sub(10);
+        }
+
+        let prepared_proof = self.prepared_blocks.iter()
+            .filter(|((prepared_height, _), _)| *prepared_height == height)
+            .max_by_key(|((_, prepared_view), _)| *prepared_view)
+            .map(|((_, prepared_view), block_hash)| PreparedProof {
+                block_hash: block_hash.clone(),
+                height,
+                view: *prepared_view,
+                preparers: self.vote_collector.voters(height, *prepared_view, Step::Prepare),
+            });
+
+        ViewChange { new_view: self.view + 1, height, prepared_proof, sender: self_key }
+    }
+
+    /// Records an incoming ViewChange for `new_view`. Once 2f+1 have been
+    /// collected, advances the local view and has the deterministically
+    /// selected new leader issue a NewView re-proposing the highest prepared
+    /// block certified across the collected messages.
+    pub fn process_view_change(&mut self, view_change: ViewChange) -> Option<NewView> {
+        let new_view = view_change.new_view;
+        self.view_changes.entry(new_view).or_insert_with(HashMap::new).insert(view_change.sender.clone(), view_change);
+
+        let collected = self.view_changes.get(&new_view)?;
+        if collected.len() < 2 * self.faulty_nodes_limit + 1 {
+            return None;
+        }
+
+        self.view = new_view;
+        let re_proposed_block_hash = collected.values()
+            .filter_map(|vc| vc.prepared_proof.as_ref())
+            .max_by_key(|proof| proof.view)
+            .map(|proof| proof.block_hash.clone());
+
+        let new_leader = Self::select_leader(new_view, &self.current_committee);
+        Some(NewView { new_view, re_proposed_block_hash, issued_by: new_leader })
+    }
+
+    /// The canonical tip at `height`: the competing block with the most
+    /// accumulated stake, used as the `prev_block_hash` a proposer builds
+    /// the next block from.
+    pub fn canonical_tip(&self, height: u64) -> Option<Hash> {
+        self.fork_choice.heaviest_fork(height)
+    }
+
     /// Selects 21 validators for the next election epoch.
     /// This is a simplified representation of the complex selection logic.
     pub fn select_election_validators(
@@ -114,10 +249,13 @@ impl NaijaConsensusEngine {
 
     /// Simulates a validator proposing a new block with collected vote transactions.
     pub fn propose_block(&self, transactions: Vec<VoteTransaction>, proposer_key: PublicKey) -> Block {
-        let prev_block_hash = vec![0; 32]; // Get from actual chain tip
+        let height = 100; // Get from actual chain height
+        // Build on the heaviest fork at the previous height rather than
+        // assuming a single uncontested predecessor, so a partition that
+        // produced competing blocks resolves to the stake-weighted winner.
+        let prev_block_hash = self.canonical_tip(height - 1).unwrap_or_else(|| vec![0; 32]);
         let merkle_root = self.calculate_merkle_root(&transactions); // SHA3-256
         let timestamp = chrono::Utc::now().timestamp() as u64;
-        let height = 100; // Get from actual chain height
 
         let header = BlockHeader {
             prev_block_hash,
@@ -126,57 +264,69 @@ impl NaijaConsensusEngine {
             height,
             validator_pub_key: proposer_key,
             pre_prepare_signatures: vec![],
-            prepare_signatures: vec![],
-            commit_signatures: vec![],
+            prepare_aggregate: Vec::new(),
+            prepare_bitfield: Bitfield::default(),
+            commit_aggregate: Vec::new(),
+            commit_bitfield: Bitfield::default(),
         };
         Block { header, transactions }
     }
 
     /// Simulates the PBFT consensus process for a block.
     /// Returns Ok(finalized_block) or Err(reason).
-    pub fn finalize_block_pbft(&mut self, mut block: Block) -> Result<Block, String> {
-        // Phase 1: Pre-Prepare (Leader proposes) - Block is already proposed.
-        // Validators verify leader's signature and block validity.
-        // If valid, they sign a 'Pre-Prepare' message and send it.
-        // Assume block.header.pre_prepare_signatures contains leader's signature.
-
-        // Phase 2: Prepare (Validators agree on block content)
-        // Each validator verifies the block. If valid, they sign and broadcast 'Prepare' message.
-        // Collect 2f+1 'Prepare' messages.
-        // For simulation, assume we receive enough signatures.
-        if block.header.prepare_signatures.len() < (2 * self.faulty_nodes_limit + 1) {
-            // In a real system, this node would wait for messages or timeout.
-            // Simulate adding this node's prepare signature if it's part of the committee.
-            // block.header.prepare_signatures.push(self.sign_message(&block.header.merkle_root));
-            return Err("Not enough prepare signatures".to_string());
+    /// Runs Prepare, Commit and finality for `block` (Pre-Prepare -- the
+    /// leader proposing it -- is assumed to have already happened). The
+    /// Prepare/Commit phase-processing flow itself lives in
+    /// `consensus_common::run_prepare_commit_phases`, shared with
+    /// `src/consensus.rs`'s `process_block_pbft`.
+    pub fn finalize_block_pbft(
+        &mut self,
+        block: Block,
+        self_key: &PublicKey,
+        private_key: &[u8],
+        prepare_votes: &[(PublicKey, Signature)],
+        commit_votes: &[(PublicKey, Signature)],
+    ) -> Result<Block, String> {
+        // The committee may have rotated in since genesis (e.g. via
+        // select_election_validators); make sure every current member has a
+        // slashing-protection history before this node's own vote is
+        // checked against it.
+        for validator in &self.current_committee {
+            self.slashing_protection.register_validator(validator.pub_key.clone());
         }
 
-        // Phase 3: Commit (Validators agree to commit block)
-        // If 2f+1 'Prepare' messages received, validator signs and broadcasts 'Commit' message.
-        // Collect 2f+1 'Commit' messages.
-        if block.header.commit_signatures.len() < (2 * self.faulty_nodes_limit + 1) {
-            // In a real system, this node would wait for messages or timeout.
-            // Simulate adding this node's commit signature.
-            // block.header.commit_signatures.push(self.sign_message(&block.header.merkle_root));
-            return Err("Not enough commit signatures".to_string());
-        }
+        let slashing_protection = &mut self.slashing_protection;
+        let finalized_block = run_prepare_commit_phases(
+            block,
+            &self.current_committee,
+            self_key,
+            self.view,
+            self.faulty_nodes_limit,
+            self.two_thirds_majority_transition,
+            &mut self.vote_collector,
+            &mut self.fork_choice,
+            &mut self.rolling_finality,
+            &mut self.prepared_blocks,
+            prepare_votes,
+            commit_votes,
+            |pub_key, height, phase, block_hash| {
+                slashing_protection.check_and_record(pub_key, height, phase, block_hash.clone())?;
+                Ok(Self::ed25519_sign(&block_hash, private_key))
+            },
+        )?;
 
-        // Phase 4: Reply/Finality
-        // Block is finalized and added to the chain.
         // Update validator reputations based on participation.
-        self.update_reputations_for_block(&block);
-        Ok(block)
+        self.update_reputations_for_block(&finalized_block);
+
+        Ok(finalized_block)
     }
 
     /// Updates validators' reputation scores based on their participation.
     fn update_reputations_for_block(&mut self, block: &Block) {
-        // Placeholder: In reality, verify signatures and get signer's pub_key
-        let committed_validators: Vec<PublicKey> = block.header.commit_signatures.iter()
-            .map(|_sig| vec![0; 32]) // Dummy pub_key from signature
-            .collect();
-
-        for validator in self.current_committee.iter_mut() {
-            if committed_validators.contains(&validator.pub_key) {
+        // Participation now comes straight from the commit bitfield rather
+        // than a per-signature list, since the block no longer carries one.
+        for (index, validator) in self.current_committee.iter_mut().enumerate() {
+            if block.header.commit_bitfield.is_set(index) {
                 validator.reputation_score = validator.reputation_score.saturating_add(1);
             } else {
                 // Validator was in committee but didn't commit
@@ -234,7 +384,7 @@ fn main() {
         Validator { pub_key: vec![20], stake: 880, reputation_score: 66, geopolitical_zone: "South-West".to_string() },
         Validator { pub_key: vec![21], stake: 1000, reputation_score: 69, geopolitical_zone: "North-Central".to_string() },
     ];
-    let mut engine = NaijaConsensusEngine::new(initial_validators.clone(), 7); // f=7 for 21 validators (2f+1 = 15 needed)
+    let mut engine = NaijaConsensusEngine::new(initial_validators.clone(), 7, 1_000_000); // f=7 for 21 validators (2f+1 = 15 needed)
 
     let committee = engine.select_election_validators(&initial_validators, 21);
     println!("Selected Committee (first 5): {:?}", &committee[0..std::cmp::min(5, committee.len())]);
@@ -243,13 +393,20 @@ fn main() {
     let dummy_tx = VoteTransaction {
         voter_pub_key: vec![10], election_id: vec![1], candidate_id: vec![2], timestamp: 0, signature: vec![0; 64]
     };
-    let mut proposed_block = engine.propose_block(vec![dummy_tx], committee[0].pub_key.clone());
+    let self_key = committee[0].pub_key.clone();
+    let self_private_key = vec![0; 32];
+    let proposed_block = engine.propose_block(vec![dummy_tx], self_key.clone());
 
-    // Simulate collecting signatures for PBFT
-    proposed_block.header.prepare_signatures = vec![vec![0;64]; 15]; // 2f+1 signatures
-    proposed_block.header.commit_signatures = vec![vec![0;64]; 15]; // 2f+1 signatures
+    // Simulate the rest of the committee's gossiped Prepare/Commit votes
+    // arriving off the wire: this node's own vote is produced inside
+    // finalize_block_pbft, the other 14 of the 2f+1 (f=7) needed come from here.
+    let dummy_votes: Vec<(PublicKey, Signature)> = initial_validators.iter()
+        .filter(|v| v.pub_key != self_key)
+        .take(14)
+        .map(|v| (v.pub_key.clone(), vec![0; 64]))
+        .collect();
 
-    match engine.finalize_block_pbft(proposed_block) {
+    match engine.finalize_block_pbft(proposed_block, &self_key, &self_private_key, &dummy_votes, &dummy_votes) {
         Ok(final_block) => println!("Block finalized successfully at height {}", final_block.header.height),
         Err(e) => println!("Block finalization failed: {}", e),
     }
@@ -3,8 +3,12 @@
 
 use std::collections::HashMap;
 
-// Represents a digital signature
-type Signature = Vec<u8>;
+mod consensus_common;
+use consensus_common::{
+    run_prepare_commit_phases, AggregateSignature, Bitfield, CommitteeMember, ForkChoice, NewView,
+    PbftBlock, PreparedProof, PublicKey, RollingFinality, Signature, SlashingProtection, Step,
+    ViewChange, VoteCollector,
+};
 
 // Represents a transaction
 #[derive(Debug, Clone)]
@@ -45,23 +49,148 @@ pub struct Block {
     transactions: Vec<Transaction>,
     // PBFT-related fields
     pre_prepare_signatures: Vec<Signature>, // Signatures from validators confirming pre-prepare
-    prepare_signatures: Vec<Signature>,    // Signatures from validators confirming prepare
-    commit_signatures: Vec<Signature>,     // Signatures from validators confirming commit
+    // Prepare/Commit phases are each collapsed to one aggregate signature
+    // plus a bitfield marking which committee members contributed, instead
+    // of one signature per validator.
+    prepare_aggregate: AggregateSignature,
+    prepare_bitfield: Bitfield,
+    commit_aggregate: AggregateSignature,
+    commit_bitfield: Bitfield,
+}
+
+impl CommitteeMember for Validator {
+    fn pub_key(&self) -> &PublicKey {
+        &self.pub_key
+    }
+
+    fn stake(&self) -> u64 {
+        self.stake
+    }
+}
+
+impl PbftBlock for Block {
+    fn height(&self) -> u64 {
+        self.header.height
+    }
+
+    fn merkle_root(&self) -> &[u8] {
+        &self.header.merkle_root
+    }
+
+    fn set_prepare_phase(&mut self, aggregate: AggregateSignature, bitfield: Bitfield) {
+        self.prepare_aggregate = aggregate;
+        self.prepare_bitfield = bitfield;
+    }
+
+    fn set_commit_phase(&mut self, aggregate: AggregateSignature, bitfield: Bitfield) {
+        self.commit_aggregate = aggregate;
+        self.commit_bitfield = bitfield;
+    }
+
+    fn commit_bitfield(&self) -> &Bitfield {
+        &self.commit_bitfield
+    }
 }
 
 // Main consensus engine struct
 pub struct NaijaConsensusEngine {
     current_validators: Vec<Validator>,
     faulty_nodes_limit: usize, // 'f' in 2f+1
+    // Height from which the rolling finality quorum switches from a simple
+    // majority (> n/2) to a 2/3 supermajority (> 2n/3) of distinct signers.
+    two_thirds_majority_transition: u64,
+    rolling_finality: RollingFinality,
+    vote_collector: VoteCollector,
+    // Current PBFT view; bumped by a successful view-change.
+    view: u64,
+    // Merkle root of the block that reached a Prepare quorum at (height, view),
+    // kept so a timed-out round can certify what a NewView must re-propose.
+    prepared_blocks: HashMap<(u64, u64), Vec<u8>>,
+    // Buffered ViewChange messages, keyed by the view being requested.
+    view_changes: HashMap<u64, HashMap<PublicKey, ViewChange>>,
+    fork_choice: ForkChoice,
+    // Guards this node's own keys against self-equivocation after a restart.
+    slashing_protection: SlashingProtection,
     // ... other state variables like chain, mempool, etc.
 }
 
 impl NaijaConsensusEngine {
-    pub fn new(initial_validators: Vec<Validator>, faulty_limit: usize) -> Self {
+    pub fn new(initial_validators: Vec<Validator>, faulty_limit: usize, two_thirds_majority_transition: u64) -> Self {
+        let mut slashing_protection = SlashingProtection::new();
+        for validator in &initial_validators {
+            slashing_protection.register_validator(validator.pub_key.clone());
+        }
+
         NaijaConsensusEngine {
             current_validators: initial_validators,
             faulty_nodes_limit: faulty_limit,
+            two_thirds_majority_transition,
+            rolling_finality: RollingFinality::new(),
+            vote_collector: VoteCollector::new(),
+            view: 0,
+            prepared_blocks: HashMap::new(),
+            view_changes: HashMap::new(),
+            fork_choice: ForkChoice::new(),
+            slashing_protection,
+        }
+    }
+
+    /// Deterministically selects the leader for `view` from the committee.
+    pub fn select_leader(view: u64, committee: &[Validator]) -> PublicKey {
+        committee[(view as usize) % committee.len()].pub_key.clone()
+    }
+
+    /// Called when this validator's round timer expires without the current
+    /// view reaching Commit. Penalizes the leader that was supposed to drive
+    /// this view, then returns the ViewChange to broadcast, carrying the
+    /// highest Prepare-quorum block seen at `height` so it isn't lost.
+    pub fn on_timeout(&mut self, height: u64, current_committee: &[Validator], self_key: PublicKey) -> ViewChange {
+        let abandoned_leader = Self::select_leader(self.view, current_committee);
+        if let Some(validator) = self.current_validators.iter_mut().find(|v| v.pub_key == abandoned_leader) {
+            validator.reputation_score = validator.reputation_score.saturating_sub(10);
         }
+
+        let prepared_proof = self.prepared_blocks.iter()
+            .filter(|((prepared_height, _), _)| *prepared_height == height)
+            .max_by_key(|((_, prepared_view), _)| *prepared_view)
+            .map(|((_, prepared_view), block_hash)| PreparedProof {
+                block_hash: block_hash.clone(),
+                height,
+                view: *prepared_view,
+                preparers: self.vote_collector.voters(height, *prepared_view, Step::Prepare),
+            });
+
+        ViewChange { new_view: self.view + 1, height, prepared_proof, sender: self_key }
+    }
+
+    /// Records an incoming ViewChange for `new_view`. Once 2f+1 have been
+    /// collected, advances the local view and has the deterministically
+    /// selected new leader issue a NewView re-proposing the highest prepared
+    /// block certified across the collected messages.
+    pub fn process_view_change(&mut self, view_change: ViewChange, current_committee: &[Validator]) -> Option<NewView> {
+        let new_view = view_change.new_view;
+        self.view_changes.entry(new_view).or_insert_with(HashMap::new).insert(view_change.sender.clone(), view_change);
+
+        let collected = self.view_changes.get(&new_view)?;
+        if collected.len() < 2 * self.faulty_nodes_limit + 1 {
+            return None;
+        }
+
+        self.view = new_view;
+        let re_proposed_block_hash = collected.values()
+            .filter_map(|vc| vc.prepared_proof.as_ref())
+            .max_by_key(|proof| proof.view)
+            .map(|proof| proof.block_hash.clone());
+
+        let new_leader = Self::select_leader(new_view, current_committee);
+        Some(NewView { new_view, re_proposed_block_hash, issued_by: new_leader })
+    }
+
+    /// The canonical tip at `height`: the competing block with the most
+    /// accumulated stake, to be used as the `prev_block_hash` a proposer
+    /// builds the next block from.
+    pub fn canonical_tip(&self, height: u64) -> Option<Vec<u8>> {
+        self.fork_choice.heaviest_fork(height)
     }
 
     /// Selects validators for the next epoch based on stake, reputation, and geolocation.
@@ -102,68 +231,61 @@ impl NaijaConsensusEngine {
 
     /// Simulates the PBFT state machine for processing a proposed block.
     /// In a real system, this would involve network communication and state transitions.
+    ///
+    /// Pre-Prepare (the leader proposing `proposed_block`) is assumed to
+    /// have already happened; this runs Prepare, Commit and finality. The
+    /// Prepare/Commit phase-processing flow itself lives in
+    /// `consensus_common::run_prepare_commit_phases`, shared with
+    /// `rust_consensus_snippet.rs`'s `finalize_block_pbft`.
     pub fn process_block_pbft(
         &mut self,
-        mut proposed_block: Block,
+        proposed_block: Block,
         current_committee: &[Validator],
+        self_key: &PublicKey,
+        prepare_votes: &[(PublicKey, Signature)],
+        commit_votes: &[(PublicKey, Signature)],
     ) -> Result<Block, String> {
-        // --- Phase 1: Pre-Prepare (Leader proposes block) ---
-        // In a real scenario, the leader would create `proposed_block` and sign it.
-        // Other validators would receive it and verify the leader's signature and block validity.
-        println!("PBFT: Pre-Prepare phase - Block proposed by leader.");
-        // Assume `proposed_block` already has leader's pre-prepare signature.
-
-        // --- Phase 2: Prepare (Validators agree on block content) ---
-        // Each validator verifies the block. If valid, they sign and broadcast a 'Prepare' message.
-        // This node collects 'Prepare' messages from other validators.
-        // For this pseudocode, we'll simulate collecting enough signatures.
-        if proposed_block.prepare_signatures.len() < (2 * self.faulty_nodes_limit + 1) {
-            // In a real system, this node would wait for more messages or timeout.
-            // For now, we'll assume it has enough or fail.
-            println!("PBFT: Waiting for enough Prepare signatures...");
-            // Simulate adding a signature if it's this node's turn
-            // proposed_block.prepare_signatures.push(self.sign_message(&proposed_block.header.merkle_root));
-        }
-
-        if proposed_block.prepare_signatures.len() < (2 * self.faulty_nodes_limit + 1) {
-            return Err("Not enough prepare signatures to proceed to Commit phase.".to_string());
-        }
-        println!("PBFT: Prepare phase complete - Enough Prepare signatures collected.");
-
-        // --- Phase 3: Commit (Validators agree to commit block) ---
-        // If 2f+1 'Prepare' messages are received, each validator signs and broadcasts a 'Commit' message.
-        // This node collects 'Commit' messages.
-        if proposed_block.commit_signatures.len() < (2 * self.faulty_nodes_limit + 1) {
-            // In a real system, this node would wait for more messages or timeout.
-            println!("PBFT: Waiting for enough Commit signatures...");
-            // Simulate adding a signature if it's this node's turn
-            // proposed_block.commit_signatures.push(self.sign_message(&proposed_block.header.merkle_root));
-        }
-
-        if proposed_block.commit_signatures.len() < (2 * self.faulty_nodes_limit + 1) {
-            return Err("Not enough commit signatures to finalize block.".to_string());
+        // The committee may have rotated in since genesis (e.g. via
+        // select_validators); make sure every current member has a slashing-
+        // protection history before this node's own vote is checked against it.
+        for validator in current_committee {
+            self.slashing_protection.register_validator(validator.pub_key.clone());
         }
-        println!("PBFT: Commit phase complete - Enough Commit signatures collected.");
 
-        // --- Phase 4: Reply/Finality ---
-        // The block is now considered finalized and can be added to the local chain.
-        println!("PBFT: Block finalized and added to chain at height {}.", proposed_block.header.height);
+        let slashing_protection = &mut self.slashing_protection;
+        let finalized_block = run_prepare_commit_phases(
+            proposed_block,
+            current_committee,
+            self_key,
+            self.view,
+            self.faulty_nodes_limit,
+            self.two_thirds_majority_transition,
+            &mut self.vote_collector,
+            &mut self.fork_choice,
+            &mut self.rolling_finality,
+            &mut self.prepared_blocks,
+            prepare_votes,
+            commit_votes,
+            |pub_key, height, phase, block_hash| {
+                slashing_protection.check_and_record(pub_key, height, phase, block_hash)?;
+                // In a real implementation, this would use Ed25519 to sign.
+                Ok(vec![0; 64]) // Dummy signature
+            },
+        )?;
 
         // Update validator reputations based on their participation in this block's finalization
-        self.update_reputations_for_block(&proposed_block, current_committee);
+        self.update_reputations_for_block(&finalized_block, current_committee);
 
-        Ok(proposed_block)
+        Ok(finalized_block)
     }
 
     /// Updates validators' reputation scores based on their participation in a block.
     pub fn update_reputations_for_block(&mut self, block: &Block, committee: &[Validator]) {
-        let participating_keys: Vec<Vec<u8>> = block.commit_signatures.iter()
-            .map(|sig| /* derive pub_key from sig */ sig.clone()) // Placeholder: In reality, verify signature and get signer's pub_key
-            .collect();
-
+        // Participation now comes straight from the commit bitfield rather
+        // than a per-signature list, since the block no longer carries one.
         for validator in self.current_validators.iter_mut() {
-            if committee.iter().any(|v| v.pub_key == validator.pub_key) { // Only update committee members
-                if participating_keys.contains(&validator.pub_key) {
+            if let Some(index) = committee.iter().position(|v| v.pub_key == validator.pub_key) {
+                if block.commit_bitfield.is_set(index) {
                     validator.reputation_score = validator.reputation_score.saturating_add(1);
                     println!("Reputation: Validator {:?} gained 1 point.", validator.pub_key);
                 } else {
@@ -176,11 +298,6 @@ impl NaijaConsensusEngine {
         }
     }
 
-    // Placeholder for signing a message (Ed25519 would be used)
-    fn sign_message(&self, _message: &[u8]) -> Signature {
-        // In a real implementation, this would use Ed25519 to sign.
-        vec![0; 64] // Dummy signature
-    }
 }
 
 // Example usage (conceptual)
@@ -193,7 +310,7 @@ fn main() {
         Validator { pub_key: vec![5], stake: 900, geopolitical_zone: "North-West".to_string(), reputation_score: 55, last_active_block: 0 },
     ];
 
-    let mut engine = NaijaConsensusEngine::new(validators.clone(), 1); // f=1, so 2f+1 = 3 signatures needed
+    let mut engine = NaijaConsensusEngine::new(validators.clone(), 1, 1_000_000); // f=1, so 2f+1 = 3 signatures needed
 
     let committee = engine.select_validators(&validators, 1, 3); // Select 3 validators, at least 1 per zone if possible
     println!("Selected Committee: {:?}", committee);
@@ -208,11 +325,24 @@ fn main() {
         },
         transactions: vec![],
         pre_prepare_signatures: vec![vec![0; 64]], // Leader's signature
-        prepare_signatures: vec![vec![0; 64], vec![0; 64], vec![0; 64]], // 3 dummy signatures
-        commit_signatures: vec![vec![0; 64], vec![0; 64], vec![0; 64]], // 3 dummy signatures
+        prepare_aggregate: Vec::new(),
+        prepare_bitfield: Bitfield::default(),
+        commit_aggregate: Vec::new(),
+        commit_bitfield: Bitfield::default(),
     };
 
-    match engine.process_block_pbft(dummy_block, &committee) {
+    // This node is the first committee member; its own Prepare/Commit votes
+    // are produced inside process_block_pbft, not supplied here.
+    let self_key = committee[0].pub_key.clone();
+
+    // Simulate the rest of the committee's gossiped Prepare/Commit votes
+    // arriving off the wire.
+    let dummy_votes: Vec<(PublicKey, Signature)> = committee.iter()
+        .filter(|v| v.pub_key != self_key)
+        .map(|v| (v.pub_key.clone(), vec![0; 64]))
+        .collect();
+
+    match engine.process_block_pbft(dummy_block, &committee, &self_key, &dummy_votes, &dummy_votes) {
         Ok(block) => println!("Block processed successfully: {:?}", block.header.height),
         Err(e) => println!("Block processing failed: {}", e),
     }
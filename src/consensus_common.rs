@@ -0,0 +1,720 @@
+// Shared PBFT-safety primitives used by both the blockchain-flavored engine
+// in this crate (consensus.rs) and the election-flavored standalone engine
+// in rust_consensus_snippet.rs at the repo root. These were previously
+// copy-pasted near-verbatim into both files; a bug in one (e.g. the
+// positional committee.iter().zip(signatures.iter()) attribution bug, since
+// fixed) existed identically in the other. Factored out here so
+// there is exactly one copy of the quorum/finality/fork-choice/slashing-
+// protection logic to get right.
+//
+// Like the files that use it, this is conceptual pseudocode and is not
+// intended to be compiled or run in this environment.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// Represents a digital signature
+pub type Signature = Vec<u8>;
+
+// Represents a validator's public key
+pub type PublicKey = Vec<u8>;
+
+// A single BLS signature aggregating many individual signatures via
+// elliptic-curve point addition; verified with one pairing check instead of
+// N individual Ed25519 checks.
+pub type AggregateSignature = Vec<u8>;
+
+/// The minimal view of a committee member the primitives below need: who
+/// they are and how much stake backs their vote. Each engine's own
+/// `Validator` type implements this, so this module never needs to know
+/// about the rest of an engine-specific validator record (reputation,
+/// geopolitical zone, etc).
+pub trait CommitteeMember {
+    fn pub_key(&self) -> &PublicKey;
+    fn stake(&self) -> u64;
+}
+
+/// Marks which committee members contributed to an aggregate signature, one
+/// bit per committee index, in committee order.
+#[derive(Debug, Clone, Default)]
+pub struct Bitfield {
+    bits: Vec<bool>,
+}
+
+impl Bitfield {
+    pub fn new(committee_size: usize) -> Self {
+        Bitfield { bits: vec![false; committee_size] }
+    }
+
+    pub fn set(&mut self, index: usize) {
+        if index < self.bits.len() {
+            self.bits[index] = true;
+        }
+    }
+
+    pub fn is_set(&self, index: usize) -> bool {
+        self.bits.get(index).copied().unwrap_or(false)
+    }
+
+    pub fn count(&self) -> usize {
+        self.bits.iter().filter(|set| **set).count()
+    }
+}
+
+/// Combines each contributing validator's signature into a single BLS
+/// aggregate signature. A 21-validator commit then costs one signature and
+/// one bitmap instead of dozens of individual 64-byte signatures.
+pub fn aggregate(signatures: &[Signature], bitfield: &Bitfield) -> AggregateSignature {
+    // Placeholder: a real implementation sums the BLS signature points.
+    let _ = bitfield;
+    signatures.iter().flatten().cloned().collect()
+}
+
+/// Verifies an aggregate signature with a single pairing check against the
+/// committee members marked in `bitfield`, rather than one check per signer.
+pub fn verify_aggregate<T: CommitteeMember>(
+    _agg: &AggregateSignature,
+    _bitfield: &Bitfield,
+    _committee: &[T],
+    _merkle_root: &[u8],
+) -> bool {
+    // Placeholder: a real implementation runs a single aggregate pairing
+    // check, e(agg, G2) == product_i e(pub_key_i, H(merkle_root)), over the
+    // committee members marked in `bitfield`. This is an unconditional
+    // stub, same as `VoteCollector::verify_ed25519` below, until real
+    // pairing verification lands.
+    true
+}
+
+/// A phase of the PBFT state machine a vote can be cast for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Step {
+    Prepare,
+    Commit,
+}
+
+/// Collects at most one counted vote per validator per (height, view, step),
+/// verifying the signature and committee membership on insert so duplicate
+/// or unverified signatures can no longer inflate a naive `Vec::len()` quorum
+/// check, and so a commit can be attributed back to its signer.
+pub struct VoteCollector {
+    votes: HashMap<(u64, u64, Step), HashMap<PublicKey, Signature>>,
+}
+
+impl VoteCollector {
+    pub fn new() -> Self {
+        VoteCollector { votes: HashMap::new() }
+    }
+
+    /// Verifies `signature` is a valid Ed25519 signature by `signer` over
+    /// `merkle_root` and that `signer` is in `current_committee`, then
+    /// records the vote. Re-inserting the same signer's vote is idempotent.
+    /// Returns whether the 2f+1 quorum for this key is met after the insert.
+    pub fn insert<T: CommitteeMember>(
+        &mut self,
+        height: u64,
+        view: u64,
+        step: Step,
+        signer: PublicKey,
+        signature: Signature,
+        merkle_root: &[u8],
+        current_committee: &[T],
+        faulty_nodes_limit: usize,
+    ) -> bool {
+        if !current_committee.iter().any(|v| v.pub_key() == &signer) {
+            return self.has_quorum(height, view, step, faulty_nodes_limit);
+        }
+        if !Self::verify_ed25519(&signer, merkle_root, &signature) {
+            return self.has_quorum(height, view, step, faulty_nodes_limit);
+        }
+        self.votes.entry((height, view, step)).or_insert_with(HashMap::new).insert(signer, signature);
+        self.has_quorum(height, view, step, faulty_nodes_limit)
+    }
+
+    pub fn has_quorum(&self, height: u64, view: u64, step: Step, faulty_nodes_limit: usize) -> bool {
+        self.votes.get(&(height, view, step))
+            .map_or(false, |voters| voters.len() >= 2 * faulty_nodes_limit + 1)
+    }
+
+    /// The distinct public keys that have voted at this (height, view, step).
+    pub fn voters(&self, height: u64, view: u64, step: Step) -> Vec<PublicKey> {
+        self.votes.get(&(height, view, step))
+            .map(|voters| voters.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Collapses the verified votes at this (height, view, step) into a
+    /// single BLS aggregate signature plus a bitfield marking which
+    /// committee members contributed, in committee order, so the block can
+    /// store one signature and one bitmap instead of one signature per voter.
+    pub fn aggregate_votes<T: CommitteeMember>(&self, height: u64, view: u64, step: Step, committee: &[T]) -> (AggregateSignature, Bitfield) {
+        let mut bitfield = Bitfield::new(committee.len());
+        let mut signatures = Vec::new();
+        if let Some(voters) = self.votes.get(&(height, view, step)) {
+            for (index, validator) in committee.iter().enumerate() {
+                if let Some(signature) = voters.get(validator.pub_key()) {
+                    bitfield.set(index);
+                    signatures.push(signature.clone());
+                }
+            }
+        }
+        (aggregate(&signatures, &bitfield), bitfield)
+    }
+
+    // Placeholder for real Ed25519 verification.
+    fn verify_ed25519(_signer: &PublicKey, _message: &[u8], _signature: &Signature) -> bool {
+        true
+    }
+}
+
+/// Tracks unfinalized blocks on top of the last finalized one and advances
+/// finality as validators accumulate distinct signers across the buffer,
+/// rather than finalizing each block in isolation the moment it hits 2f+1.
+pub struct RollingFinality {
+    /// Unfinalized blocks in height order: (block_hash, height, commit signers).
+    buffered_blocks: VecDeque<(Vec<u8>, u64, Vec<Vec<u8>>)>,
+    /// Hashes that have been walked past quorum and are now final.
+    finalized: Vec<Vec<u8>>,
+}
+
+impl RollingFinality {
+    pub fn new() -> Self {
+        RollingFinality {
+            buffered_blocks: VecDeque::new(),
+            finalized: Vec::new(),
+        }
+    }
+
+    /// Buffers a newly-committed block's signer set and re-walks the buffer
+    /// to advance finality as far as the distinct-signer quorum allows.
+    pub fn push_block(
+        &mut self,
+        block_hash: Vec<u8>,
+        height: u64,
+        signers: Vec<Vec<u8>>,
+        committee_size: usize,
+        two_thirds_majority_transition: u64,
+    ) {
+        self.buffered_blocks.push_back((block_hash, height, signers));
+        self.advance_finality(committee_size, two_thirds_majority_transition);
+    }
+
+    /// Walks the buffer oldest-to-newest accumulating the set of distinct
+    /// signers seen so far, and finalizes every block up to the first point
+    /// where that set exceeds the quorum required at that block's height.
+    fn advance_finality(&mut self, committee_size: usize, two_thirds_majority_transition: u64) {
+        let mut distinct_signers: HashSet<Vec<u8>> = HashSet::new();
+        let mut finalize_through = 0usize;
+
+        for (i, (_, height, signers)) in self.buffered_blocks.iter().enumerate() {
+            distinct_signers.extend(signers.iter().cloned());
+            let required = Self::quorum_threshold(committee_size, *height, two_thirds_majority_transition);
+            if distinct_signers.len() > required {
+                finalize_through = i + 1;
+            }
+        }
+
+        for _ in 0..finalize_through {
+            if let Some((hash, _, _)) = self.buffered_blocks.pop_front() {
+                self.finalized.push(hash);
+            }
+        }
+    }
+
+    /// The count of distinct signers a block at `height` must exceed to be
+    /// finalized: half the committee below the transition height (a simple
+    /// majority), two thirds of it at or above (a 2/3 supermajority). The switch closes the long-range "attack of the clones" window
+    /// where two competing chains can each gather a bare majority.
+    fn quorum_threshold(committee_size: usize, height: u64, two_thirds_majority_transition: u64) -> usize {
+        if height >= two_thirds_majority_transition {
+            (2 * committee_size) / 3
+        } else {
+            committee_size / 2
+        }
+    }
+
+    /// Drops a validator from the signer bookkeeping, e.g. after a validator
+    /// set change removes it from the committee.
+    pub fn remove_signers(&mut self, pub_key: &[u8]) {
+        for (_, _, signers) in self.buffered_blocks.iter_mut() {
+            signers.retain(|s| s.as_slice() != pub_key);
+        }
+    }
+
+    pub fn is_finalized(&self, block_hash: &[u8]) -> bool {
+        self.finalized.iter().any(|h| h.as_slice() == block_hash)
+    }
+}
+
+/// Certifies that a block reached a Prepare quorum in a given view, so a
+/// future NewView can safely re-propose it instead of losing the work.
+#[derive(Debug, Clone)]
+pub struct PreparedProof {
+    pub block_hash: Vec<u8>,
+    pub height: u64,
+    pub view: u64,
+    pub preparers: Vec<PublicKey>,
+}
+
+/// Broadcast by a validator whose round timer expires without the current
+/// view reaching Commit. Carries the highest Prepare-quorum block this node
+/// has seen so the new leader can re-propose it rather than lose it.
+#[derive(Debug, Clone)]
+pub struct ViewChange {
+    pub new_view: u64,
+    pub height: u64,
+    pub prepared_proof: Option<PreparedProof>,
+    pub sender: PublicKey,
+}
+
+/// Issued by the newly-selected leader once it collects 2f+1 ViewChange
+/// messages for `new_view`; re-proposes the highest prepared block certified
+/// across them so no committed value is lost.
+#[derive(Debug, Clone)]
+pub struct NewView {
+    pub new_view: u64,
+    pub re_proposed_block_hash: Option<Vec<u8>>,
+    /// The validator `select_leader(new_view, committee)` deterministically
+    /// picked for this view, so a recipient can check this NewView actually
+    /// came from the leader it was entitled to come from.
+    pub issued_by: PublicKey,
+}
+
+/// Tracks, per height, the stake-weighted vote each competing block hash has
+/// accumulated during a network partition, and enforces a lockout rule so a
+/// validator that commits to one fork cannot immediately jump to a
+/// conflicting one.
+pub struct ForkChoice {
+    /// height -> (block_hash -> stake-weighted vote total).
+    votes_by_height: HashMap<u64, HashMap<Vec<u8>, u64>>,
+    /// pub_key -> (height, block_hash, lockout_expiry) of the validator's last vote.
+    lockouts: HashMap<PublicKey, (u64, Vec<u8>, u64)>,
+    /// pub_key -> consecutive votes cast for the same fork, doubling the lockout each time.
+    confirmations: HashMap<PublicKey, u32>,
+}
+
+impl ForkChoice {
+    pub fn new() -> Self {
+        ForkChoice {
+            votes_by_height: HashMap::new(),
+            lockouts: HashMap::new(),
+            confirmations: HashMap::new(),
+        }
+    }
+
+    /// Records `validator`'s stake-weighted vote for `block_hash` at
+    /// `height`. Refuses the vote if it would conflict with the validator's
+    /// still-active lockout on a different fork: that is itself a slashable
+    /// equivocation. Otherwise extends the confirmation streak when voting
+    /// the same fork again, doubling the lockout window, or resets it to 1
+    /// when switching forks.
+    pub fn record_vote<T: CommitteeMember>(&mut self, validator: &T, height: u64, block_hash: Vec<u8>) -> Result<(), String> {
+        if self.is_locked_out(validator.pub_key(), height, &block_hash) {
+            return Err("Validator is locked out of this conflicting fork".to_string());
+        }
+
+        let same_fork = self.lockouts.get(validator.pub_key())
+            .map_or(false, |(_, locked_hash, _)| locked_hash == &block_hash);
+        let confirmations = self.confirmations.entry(validator.pub_key().clone()).or_insert(0);
+        *confirmations = if same_fork { confirmations.saturating_add(1) } else { 1 };
+
+        *self.votes_by_height.entry(height).or_insert_with(HashMap::new)
+            .entry(block_hash.clone()).or_insert(0) += validator.stake();
+
+        let lockout_expiry = height + 2u64.saturating_pow(*confirmations);
+        self.lockouts.insert(validator.pub_key().clone(), (height, block_hash, lockout_expiry));
+        Ok(())
+    }
+
+    /// The block hash with the most accumulated stake at `height`. Ties are
+    /// broken on the lexicographically greatest block hash rather than
+    /// `HashMap` iteration order, which is seeded per-instance and would let
+    /// two honest validators disagree on the winner of an exactly-tied fork.
+    pub fn heaviest_fork(&self, height: u64) -> Option<Vec<u8>> {
+        self.votes_by_height.get(&height)
+            .and_then(|forks| forks.iter().max_by_key(|(hash, stake)| (**stake, hash.clone())).map(|(hash, _)| hash.clone()))
+    }
+
+    /// Whether `pub_key` is still locked out of voting for a fork other than
+    /// the one it last voted for at `height`.
+    pub fn is_locked_out(&self, pub_key: &PublicKey, height: u64, block_hash: &[u8]) -> bool {
+        self.lockouts.get(pub_key).map_or(false, |(_, locked_hash, expiry)| {
+            height < *expiry && locked_hash.as_slice() != block_hash
+        })
+    }
+}
+
+/// Why `SlashingProtection` refused to let a vote be signed: it would
+/// equivocate against something this validator already signed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlashingRisk {
+    /// `height` was not strictly greater than the last height this
+    /// validator signed a vote of this phase for.
+    HeightNotIncreasing { last_signed_height: u64 },
+    /// `height` was already signed for this phase, but for a different
+    /// block hash than the one now being requested.
+    ConflictingBlockHash { height: u64, previously_signed_hash: Vec<u8> },
+    /// The signer has not been registered with the store, so there is no
+    /// history to check against.
+    UnregisteredValidator,
+}
+
+/// The highest height (and block hash) a single validator has produced a
+/// Prepare and a Commit vote for.
+#[derive(Debug, Clone, Default)]
+struct SigningRecord {
+    highest_prepare: Option<(u64, Vec<u8>)>,
+    highest_commit: Option<(u64, Vec<u8>)>,
+}
+
+/// A snapshot of a `SlashingProtection` store's signing history, suitable
+/// for writing to disk on shutdown and reloading on restart, or carrying to
+/// new hardware when a validator key moves.
+#[derive(Debug, Clone, Default)]
+pub struct SlashingProtectionSnapshot {
+    pub entries: Vec<(PublicKey, Option<(u64, Vec<u8>)>, Option<(u64, Vec<u8>)>)>,
+}
+
+/// Double-sign protection for this node's own validator keys. A restarted
+/// validator with no memory of its prior votes can be tricked (or, after a
+/// crash, can accidentally try) into re-signing a height it already voted
+/// on, which the network's equivocation detector would then slash. Every
+/// vote about to be signed is checked against the highest height (and
+/// hash) previously signed for that phase, so the history must be
+/// imported before first use on a given machine.
+pub struct SlashingProtection {
+    records: HashMap<PublicKey, SigningRecord>,
+}
+
+impl SlashingProtection {
+    pub fn new() -> Self {
+        SlashingProtection { records: HashMap::new() }
+    }
+
+    /// Starts tracking `pub_key`, if it isn't already. Registering twice is
+    /// a no-op; it never resets an existing history.
+    pub fn register_validator(&mut self, pub_key: PublicKey) {
+        self.records.entry(pub_key).or_insert_with(SigningRecord::default);
+    }
+
+    /// Consulted before a Prepare or Commit vote is signed. Refuses to sign
+    /// if `height` is not strictly greater than the last height signed for
+    /// `phase`, or if `height` was already signed with a different
+    /// `block_hash`; otherwise records the new high-water mark.
+    pub fn check_and_record(
+        &mut self,
+        pub_key: &PublicKey,
+        height: u64,
+        phase: Step,
+        block_hash: Vec<u8>,
+    ) -> Result<(), SlashingRisk> {
+        let record = self.records.get_mut(pub_key).ok_or(SlashingRisk::UnregisteredValidator)?;
+        let highest = match phase {
+            Step::Prepare => &mut record.highest_prepare,
+            Step::Commit => &mut record.highest_commit,
+        };
+
+        if let Some((last_height, last_hash)) = highest {
+            if height == *last_height && last_hash != &block_hash {
+                return Err(SlashingRisk::ConflictingBlockHash {
+                    height,
+                    previously_signed_hash: last_hash.clone(),
+                });
+            }
+            if height <= *last_height {
+                return Err(SlashingRisk::HeightNotIncreasing { last_signed_height: *last_height });
+            }
+        }
+
+        *highest = Some((height, block_hash));
+        Ok(())
+    }
+
+    /// Exports the full signing history so it can be persisted across a
+    /// restart or transferred when a key moves to new hardware.
+    pub fn export(&self) -> SlashingProtectionSnapshot {
+        SlashingProtectionSnapshot {
+            entries: self.records.iter()
+                .map(|(pub_key, record)| (pub_key.clone(), record.highest_prepare.clone(), record.highest_commit.clone()))
+                .collect(),
+        }
+    }
+
+    /// Rebuilds a store from a previously-exported snapshot, e.g. on
+    /// process startup.
+    pub fn import(snapshot: SlashingProtectionSnapshot) -> Self {
+        let records = snapshot.entries.into_iter()
+            .map(|(pub_key, highest_prepare, highest_commit)| (pub_key, SigningRecord { highest_prepare, highest_commit }))
+            .collect();
+        SlashingProtection { records }
+    }
+}
+
+/// The parts of a PBFT block the shared phase-processing flow in
+/// `run_prepare_commit_phases` needs to read and write: its height and
+/// merkle root, and the aggregate-signature/bitfield pair left behind by
+/// each of the Prepare and Commit phases. Each engine implements this for
+/// its own `Block` type, which otherwise differs (e.g. whether the
+/// aggregate/bitfield pairs live directly on `Block` or nested under a
+/// `BlockHeader`), so the phase-processing logic itself only has to exist
+/// once.
+pub trait PbftBlock {
+    fn height(&self) -> u64;
+    fn merkle_root(&self) -> &[u8];
+    fn set_prepare_phase(&mut self, aggregate: AggregateSignature, bitfield: Bitfield);
+    fn set_commit_phase(&mut self, aggregate: AggregateSignature, bitfield: Bitfield);
+    fn commit_bitfield(&self) -> &Bitfield;
+}
+
+/// Runs the Prepare and Commit phases of PBFT against `block`. Shared by
+/// both engines in this repo so a bug in this flow -- like the positional
+/// `committee.iter().zip(votes.iter())` attribution bug fixed alongside
+/// chunk0-2, or the missing fork-choice canonicality gate fixed alongside
+/// chunk0-1 -- only has to be found and fixed once, instead of once per
+/// copy.
+///
+/// Folds `prepare_votes`/`commit_votes` into `vote_collector`, plus this
+/// node's own vote at each phase produced via `sign` (expected to consult
+/// the engine's `SlashingProtection` store before signing, so a restarted
+/// node can't be made to re-sign a height it already voted on). Attribution
+/// comes from each vote's own `signer` field, never from its position in
+/// `current_committee` -- a `committee.iter().zip(votes.iter())` walk would
+/// silently misattribute a vote to whichever validator happens to share its
+/// index, not the validator who actually signed it.
+///
+/// Checks each phase's 2f+1 quorum, verifies the resulting aggregate Commit
+/// signature, certifies the Prepare-quorum value in `prepared_blocks`,
+/// records the block's stake-weighted fork-choice vote, and -- only once
+/// fork-choice considers `block` canonical at its height -- feeds its
+/// commit signers into `rolling_finality`. Without that gate, two
+/// conflicting blocks seen during a partition (each reaching its own 2f+1
+/// commit quorum) would both land in the rolling-finality buffer, and the
+/// distinct-signer walk would count signers from both forks toward
+/// finalizing earlier blocks -- the exact cross-fork equivocation window
+/// rolling finality exists to close, just moved one layer up.
+#[allow(clippy::too_many_arguments)]
+pub fn run_prepare_commit_phases<T: CommitteeMember, B: PbftBlock>(
+    mut block: B,
+    current_committee: &[T],
+    self_key: &PublicKey,
+    view: u64,
+    faulty_nodes_limit: usize,
+    two_thirds_majority_transition: u64,
+    vote_collector: &mut VoteCollector,
+    fork_choice: &mut ForkChoice,
+    rolling_finality: &mut RollingFinality,
+    prepared_blocks: &mut HashMap<(u64, u64), Vec<u8>>,
+    prepare_votes: &[(PublicKey, Signature)],
+    commit_votes: &[(PublicKey, Signature)],
+    mut sign: impl FnMut(&PublicKey, u64, Step, Vec<u8>) -> Result<Signature, SlashingRisk>,
+) -> Result<B, String> {
+    let height = block.height();
+    let merkle_root = block.merkle_root().to_vec();
+
+    let own_prepare_signature = sign(self_key, height, Step::Prepare, merkle_root.clone())
+        .map_err(|risk| format!("Refusing to cast Prepare vote: {:?}", risk))?;
+    vote_collector.insert(
+        height, view, Step::Prepare,
+        self_key.clone(), own_prepare_signature,
+        &merkle_root, current_committee, faulty_nodes_limit,
+    );
+    for (signer, sig) in prepare_votes {
+        vote_collector.insert(
+            height, view, Step::Prepare,
+            signer.clone(), sig.clone(),
+            &merkle_root, current_committee, faulty_nodes_limit,
+        );
+    }
+    // Collapse the verified Prepare votes into one aggregate signature and
+    // a committee bitfield; the 2f+1 quorum check counts set bits instead
+    // of a raw signature-vector length.
+    let (prepare_aggregate, prepare_bitfield) =
+        vote_collector.aggregate_votes(height, view, Step::Prepare, current_committee);
+    if prepare_bitfield.count() < (2 * faulty_nodes_limit + 1) {
+        return Err("Not enough prepare signatures to proceed to Commit phase.".to_string());
+    }
+    block.set_prepare_phase(prepare_aggregate, prepare_bitfield);
+    // Certify this as the prepared value for (height, view) so a later
+    // view-change can re-propose it instead of losing the work.
+    prepared_blocks.insert((height, view), merkle_root.clone());
+
+    let own_commit_signature = sign(self_key, height, Step::Commit, merkle_root.clone())
+        .map_err(|risk| format!("Refusing to cast Commit vote: {:?}", risk))?;
+    vote_collector.insert(
+        height, view, Step::Commit,
+        self_key.clone(), own_commit_signature,
+        &merkle_root, current_committee, faulty_nodes_limit,
+    );
+    for (signer, sig) in commit_votes {
+        vote_collector.insert(
+            height, view, Step::Commit,
+            signer.clone(), sig.clone(),
+            &merkle_root, current_committee, faulty_nodes_limit,
+        );
+    }
+    let (commit_aggregate, commit_bitfield) =
+        vote_collector.aggregate_votes(height, view, Step::Commit, current_committee);
+    if commit_bitfield.count() < (2 * faulty_nodes_limit + 1) {
+        return Err("Not enough commit signatures to finalize block.".to_string());
+    }
+    if !verify_aggregate(&commit_aggregate, &commit_bitfield, current_committee, &merkle_root) {
+        return Err("Commit aggregate signature failed verification.".to_string());
+    }
+    block.set_commit_phase(commit_aggregate, commit_bitfield);
+
+    // Record each committing validator's stake-weighted vote for this
+    // block in the fork-choice tracker, so a competing block proposed
+    // during a partition can be compared by accumulated stake rather
+    // than assumed to be the only candidate at this height.
+    for (index, validator) in current_committee.iter().enumerate() {
+        if block.commit_bitfield().is_set(index) {
+            let _ = fork_choice.record_vote(validator, height, merkle_root.clone());
+        }
+    }
+
+    // Feed the commit signers into the rolling finality buffer, but only
+    // for the block that fork-choice currently considers canonical at
+    // this height. Without this gate, two conflicting blocks seen during
+    // a partition (each reaching its own 2f+1 commit quorum) would both
+    // land in the same buffer, and the distinct-signer walk would count
+    // signers from both forks toward finalizing earlier blocks -- the
+    // exact cross-fork equivocation window rolling finality exists to
+    // close, just moved one layer up.
+    let is_canonical = fork_choice.heaviest_fork(height).as_deref() == Some(merkle_root.as_slice());
+    if !is_canonical {
+        return Ok(block);
+    }
+
+    let commit_signers: Vec<PublicKey> = current_committee.iter().enumerate()
+        .filter(|(index, _)| block.commit_bitfield().is_set(*index))
+        .map(|(_, validator)| validator.pub_key().clone())
+        .collect();
+    rolling_finality.push_block(merkle_root.clone(), height, commit_signers, current_committee.len(), two_thirds_majority_transition);
+
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestMember {
+        pub_key: PublicKey,
+        stake: u64,
+    }
+
+    impl CommitteeMember for TestMember {
+        fn pub_key(&self) -> &PublicKey {
+            &self.pub_key
+        }
+
+        fn stake(&self) -> u64 {
+            self.stake
+        }
+    }
+
+    fn member(id: u8, stake: u64) -> TestMember {
+        TestMember { pub_key: vec![id], stake }
+    }
+
+    #[test]
+    fn vote_collector_reaches_quorum_at_2f_plus_1() {
+        let committee = vec![member(1, 1), member(2, 1), member(3, 1), member(4, 1)];
+        let mut collector = VoteCollector::new();
+        // f = 1, so quorum is 2f+1 = 3.
+        assert!(!collector.insert(10, 0, Step::Prepare, vec![1], vec![], b"root", &committee, 1));
+        assert!(!collector.insert(10, 0, Step::Prepare, vec![2], vec![], b"root", &committee, 1));
+        assert!(collector.insert(10, 0, Step::Prepare, vec![3], vec![], b"root", &committee, 1));
+    }
+
+    #[test]
+    fn vote_collector_ignores_non_committee_signer() {
+        let committee = vec![member(1, 1), member(2, 1), member(3, 1)];
+        let mut collector = VoteCollector::new();
+        collector.insert(10, 0, Step::Prepare, vec![99], vec![], b"root", &committee, 1);
+        assert!(collector.voters(10, 0, Step::Prepare).is_empty());
+    }
+
+    #[test]
+    fn vote_collector_is_idempotent_on_repeated_signer() {
+        let committee = vec![member(1, 1), member(2, 1), member(3, 1)];
+        let mut collector = VoteCollector::new();
+        collector.insert(10, 0, Step::Prepare, vec![1], vec![], b"root", &committee, 1);
+        collector.insert(10, 0, Step::Prepare, vec![1], vec![], b"root", &committee, 1);
+        assert_eq!(collector.voters(10, 0, Step::Prepare).len(), 1);
+    }
+
+    #[test]
+    fn rolling_finality_uses_simple_majority_below_transition() {
+        let mut finality = RollingFinality::new();
+        // committee_size = 4, below the transition so quorum is a simple
+        // majority (> 4/2 = 2), i.e. 3 distinct signers.
+        finality.push_block(vec![1], 5, vec![vec![1], vec![2]], 4, 100);
+        assert!(!finality.is_finalized(&[1]));
+        finality.push_block(vec![2], 6, vec![vec![3]], 4, 100);
+        assert!(finality.is_finalized(&[1]));
+    }
+
+    #[test]
+    fn rolling_finality_switches_to_two_thirds_at_transition() {
+        let mut finality = RollingFinality::new();
+        // committee_size = 6, at/above the transition so quorum is a 2/3
+        // supermajority of 4, requiring 5 distinct signers to exceed it; 3
+        // alone is not enough.
+        finality.push_block(vec![1], 100, vec![vec![1], vec![2], vec![3]], 6, 100);
+        assert!(!finality.is_finalized(&[1]));
+        finality.push_block(vec![2], 101, vec![vec![4], vec![5]], 6, 100);
+        assert!(finality.is_finalized(&[1]));
+    }
+
+    #[test]
+    fn fork_choice_heaviest_fork_breaks_ties_deterministically() {
+        let mut fork_choice = ForkChoice::new();
+        let a = member(1, 5);
+        let b = member(2, 5);
+        fork_choice.record_vote(&a, 10, vec![2]).unwrap();
+        fork_choice.record_vote(&b, 10, vec![1]).unwrap();
+        // Stakes are tied at 5 each; the lexicographically greatest hash
+        // (`[2]`) must win regardless of HashMap iteration order.
+        assert_eq!(fork_choice.heaviest_fork(10), Some(vec![2]));
+    }
+
+    #[test]
+    fn fork_choice_locks_out_conflicting_fork_vote() {
+        let mut fork_choice = ForkChoice::new();
+        let validator = member(1, 10);
+        fork_choice.record_vote(&validator, 10, vec![1]).unwrap();
+        let result = fork_choice.record_vote(&validator, 10, vec![2]);
+        assert!(result.is_err());
+        assert!(fork_choice.is_locked_out(&validator.pub_key, 10, &[2]));
+    }
+
+    #[test]
+    fn slashing_protection_rejects_non_increasing_height() {
+        let mut protection = SlashingProtection::new();
+        let pub_key = vec![1];
+        protection.register_validator(pub_key.clone());
+        protection.check_and_record(&pub_key, 10, Step::Prepare, vec![1]).unwrap();
+        let result = protection.check_and_record(&pub_key, 9, Step::Prepare, vec![1]);
+        assert_eq!(result, Err(SlashingRisk::HeightNotIncreasing { last_signed_height: 10 }));
+    }
+
+    #[test]
+    fn slashing_protection_rejects_conflicting_hash_at_same_height() {
+        let mut protection = SlashingProtection::new();
+        let pub_key = vec![1];
+        protection.register_validator(pub_key.clone());
+        protection.check_and_record(&pub_key, 10, Step::Commit, vec![1]).unwrap();
+        let result = protection.check_and_record(&pub_key, 10, Step::Commit, vec![2]);
+        assert_eq!(
+            result,
+            Err(SlashingRisk::ConflictingBlockHash { height: 10, previously_signed_hash: vec![1] })
+        );
+    }
+
+    #[test]
+    fn slashing_protection_rejects_unregistered_validator() {
+        let mut protection = SlashingProtection::new();
+        let result = protection.check_and_record(&vec![1], 10, Step::Prepare, vec![1]);
+        assert_eq!(result, Err(SlashingRisk::UnregisteredValidator));
+    }
+}